@@ -1,70 +1,447 @@
 // SP1 ZK Proof Program for Order Validation
 // Validates perp order commitments and generates proofs without revealing sensitive data
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+extern crate alloc;
+use alloc::vec::Vec;
+
+#[cfg(feature = "sp1")]
 use sp1_zkvm::prelude::*;
-use sha2::{Sha256, Digest};
+#[cfg(feature = "sha256-commitments")]
+use sha2::{Digest, Sha256};
+
+mod batch;
+mod pedersen;
+mod poseidon;
+pub use batch::{BatchResult, BatchValidator};
+use pedersen::{commit_value, value_base, Point};
+use poseidon::{hash_poseidon, pack_bytes, pack_u64, Fr};
 
 /// Order commitment structure
 #[derive(Debug, Clone)]
-struct OrderCommitment {
-    commitment: [u8; 32],      // Hash of order data
-    nullifier: [u8; 32],      // Prevents double-spending
-    balance_hash: [u8; 32],   // Hash of user balance
+pub struct OrderCommitment {
+    pub commitment: [u8; 32],      // Hash of order data
+    pub nullifier: [u8; 32],      // Prevents double-spending
+    pub balance_hash: [u8; 32],   // Hash of user balance
+}
+
+/// Authentication path into a Merkle accumulator.
+///
+/// `leaf_index` is the leaf's position from the left; bit `d` of the index
+/// selects whether the leaf sits on the left (0) or right (1) of its sibling
+/// at depth `d`. `siblings` is ordered leaf-to-root.
+#[derive(Debug, Clone)]
+pub struct MerkleProof {
+    leaf_index: u64,
+    siblings: Vec<[u8; 32]>,
+}
+
+/// A hidden value note: an amount, the asset it is denominated in, and the
+/// blinding factor for its commitment.
+#[derive(Debug, Clone)]
+pub struct ValueNote {
+    value: u64,
+    asset_id: [u8; 32],
+    blind: [u8; 32],
+}
+
+/// A leg that spends an existing shielded note (Sapling-style).
+///
+/// The public part is `nullifier`, `anchor` (the commitment-tree root the note
+/// was proven against) and `value_commitment`; the remaining fields are the
+/// private witness used to re-derive and check them in-circuit.
+#[derive(Debug, Clone)]
+pub struct SpendDescription {
+    nullifier: [u8; 32],
+    anchor: [u8; 32],
+    value_commitment: [u8; 64],
+    // Witness.
+    note: ValueNote,
+    commitment: [u8; 32],
+    commitment_proof: MerkleProof,
+    nullifier_proof: MerkleProof,
+    identity_secret: [u8; 32],
+    external_nullifier: Vec<u8>,
+}
+
+/// A leg that creates a new shielded note (margin locked or change returned).
+#[derive(Debug, Clone)]
+pub struct OutputDescription {
+    note_commitment: [u8; 32],
+    value_commitment: [u8; 64],
+    // Witness.
+    note: ValueNote,
 }
 
 /// Order validation result
+#[cfg(feature = "sp1")]
 #[derive(Debug, Clone)]
 struct ValidationResult {
     is_valid: bool,
     commitment_valid: bool,
     balance_sufficient: bool,
     nullifier_unused: bool,
+    value_balanced: bool,
+    spends_valid: Vec<bool>,
+    outputs_valid: Vec<bool>,
 }
 
+/// Default leaf of the sparse nullifier tree. An authentication path that
+/// recomputes the root with this leaf proves the nullifier is *absent* (fresh).
+const EMPTY_LEAF: [u8; 32] = [0u8; 32];
+
 /// Main entry point for SP1 program
 /// Validates perp order commitments and generates proofs
+#[cfg(feature = "sp1")]
 #[sp1_main]
 fn main() {
     // Read public values from host (order commitment, nullifier, balance proof)
     let order_commitment = env::read::<OrderCommitment>();
     
-    // Read private values (actual order data, user balance, nullifier set)
+    // Read private values (actual order data, user balance, membership paths)
     let order_data = env::read::<Vec<u8>>();
     let user_balance = env::read::<u64>();
     let required_margin = env::read::<u64>();
-    let nullifier_set = env::read::<Vec<[u8; 32]>>();
-    
+    // Identity secret stays private; the external nullifier scopes this signal to a
+    // single (market, epoch) so the same identity can't double-signal elsewhere.
+    let identity_secret = env::read::<[u8; 32]>();
+    let external_nullifier = env::read::<Vec<u8>>();
+    // Spend/output descriptions for the shielded position adjustment (open, partial
+    // close, change) plus the publicly declared net value balance.
+    let spends = env::read::<Vec<SpendDescription>>();
+    let outputs = env::read::<Vec<OutputDescription>>();
+    // Net value balance declared publicly per asset (asset id -> signed amount).
+    let value_balance = env::read::<Vec<([u8; 32], i64)>>();
+    // Public accumulator roots: deposited commitments and spent nullifiers.
+    let commitment_root = env::read::<[u8; 32]>();
+    let nullifier_root = env::read::<[u8; 32]>();
+    // Witnesses: inclusion of the order's commitment and freshness of its nullifier.
+    let commitment_proof = env::read::<MerkleProof>();
+    let nullifier_proof = env::read::<MerkleProof>();
+
     // Validate order commitment
-    let commitment_valid = validate_commitment(&order_data, &order_commitment.commitment);
-    
+    let commitment_valid = validate_commitment(&order_data, &order_commitment.commitment)
+        && verify_merkle_path(&order_commitment.commitment, &commitment_proof, &commitment_root);
+
     // Check balance sufficiency (without revealing actual balance)
     let balance_sufficient = user_balance >= required_margin;
     let balance_hash = hash_balance(user_balance);
-    
-    // Check nullifier hasn't been used
-    let nullifier_unused = !nullifier_set.contains(&order_commitment.nullifier);
-    
+
+    // Derive the nullifier in-circuit instead of trusting the host, binding it to
+    // this signer and this (market, epoch). A host can no longer supply an
+    // arbitrary nullifier unrelated to the actual identity.
+    let nullifier = derive_nullifier(&identity_secret, &external_nullifier);
+    let nullifier_bound = nullifier == order_commitment.nullifier;
+
+    // Nullifier is fresh iff the empty default still occupies *its* slot.
+    let nullifier_unused =
+        nullifier_bound && verify_nullifier_absence(&nullifier, &nullifier_proof, &nullifier_root);
+
+    // Validate every spend and output description, accumulating their value
+    // commitments into a running balance that must net against `value_balance`
+    // (librustzcash's check_spend / check_output / final_check flow).
+    let mut balance_ctx = BalanceContext::new();
+    let spends_valid: Vec<bool> = spends
+        .iter()
+        .map(|s| check_spend(s, &commitment_root, &nullifier_root, &mut balance_ctx))
+        .collect();
+    let outputs_valid: Vec<bool> = outputs
+        .iter()
+        .map(|o| check_output(o, &mut balance_ctx))
+        .collect();
+    let (net_commitment, value_balanced) = balance_ctx.final_check(&value_balance);
+
     // Overall validation result
-    let is_valid = commitment_valid && balance_sufficient && nullifier_unused;
-    
+    let is_valid = commitment_valid
+        && balance_sufficient
+        && nullifier_unused
+        && value_balanced
+        && spends_valid.iter().all(|v| *v)
+        && outputs_valid.iter().all(|v| *v);
+
     // Create validation result
     let result = ValidationResult {
         is_valid,
         commitment_valid,
         balance_sufficient,
         nullifier_unused,
+        value_balanced,
+        spends_valid,
+        outputs_valid,
     };
-    
+
+    // Public accumulator updates: the nullifiers a sequencer must insert into the
+    // spent-set tree and the new note commitments it must append to the commitment
+    // tree. Without these the proof couldn't actually drive accumulator state.
+    let spent_nullifiers: Vec<[u8; 32]> = spends.iter().map(|s| s.nullifier).collect();
+    let new_note_commitments: Vec<[u8; 32]> =
+        outputs.iter().map(|o| o.note_commitment).collect();
+
     // Commit public results (proof that validation passed without revealing private data)
     env::commit(&order_commitment.commitment);
     env::commit(&order_commitment.nullifier);
     env::commit(&balance_hash);
+    env::commit(&commitment_root);
+    env::commit(&nullifier_root);
+    env::commit(&net_commitment.to_bytes());
+    env::commit(&value_balance);
+    env::commit(&spent_nullifiers);
+    env::commit(&new_note_commitments);
     env::commit(&result);
 }
 
+/// Running value-balance accumulator, threaded through every spend and output.
+///
+/// Two parallel accumulators are maintained. `net` homomorphically combines the
+/// Pedersen value commitments (spends added, outputs subtracted) and is committed
+/// publicly as the transaction's net value-balance point — the homomorphic output
+/// the hook exposes. Conservation itself is decided by the plaintext per-asset
+/// `sums`: `final_check` compares each asset's signed sum to its declared balance.
+/// Because the notes' amounts are in-circuit witnesses, the plaintext check is
+/// sound here; `net` is the published artifact, not the thing being checked (a
+/// curve-level `net == sum_asset(balance*G_asset) + blind*H` check would need the
+/// aggregate blinding scalar, which only the batch binding step carries).
+struct BalanceContext {
+    net: Point,
+    /// Signed value sum per asset id.
+    sums: Vec<([u8; 32], i128)>,
+}
+
+impl BalanceContext {
+    fn new() -> BalanceContext {
+        BalanceContext {
+            net: Point::IDENTITY,
+            sums: Vec::new(),
+        }
+    }
+
+    fn asset_sum(&mut self, asset_id: &[u8; 32]) -> &mut i128 {
+        if let Some(pos) = self.sums.iter().position(|(a, _)| a == asset_id) {
+            &mut self.sums[pos].1
+        } else {
+            self.sums.push((*asset_id, 0));
+            &mut self.sums.last_mut().unwrap().1
+        }
+    }
+
+    fn add_spend(&mut self, cv: &Point, note: &ValueNote) {
+        self.net = self.net.add(cv);
+        *self.asset_sum(&note.asset_id) += note.value as i128;
+    }
+
+    fn add_output(&mut self, cv: &Point, note: &ValueNote) {
+        self.net = self.net.add(&cv.negate());
+        *self.asset_sum(&note.asset_id) -= note.value as i128;
+    }
+
+    /// Returns the net commitment point and whether value is conserved for every
+    /// asset: each asset's signed sum must match its declared balance (default 0),
+    /// and every declared non-zero balance must correspond to a seen asset.
+    fn final_check(&self, value_balance: &[([u8; 32], i64)]) -> (Point, bool) {
+        let mut balanced = true;
+        for (asset, sum) in &self.sums {
+            let declared = declared_balance(value_balance, asset);
+            if *sum != declared as i128 {
+                balanced = false;
+            }
+        }
+        for (asset, declared) in value_balance {
+            if *declared != 0 && !self.sums.iter().any(|(a, _)| a == asset) {
+                balanced = false;
+            }
+        }
+        (self.net, balanced)
+    }
+}
+
+/// Declared net balance for `asset`, defaulting to zero when not listed.
+fn declared_balance(value_balance: &[([u8; 32], i64)], asset: &[u8; 32]) -> i64 {
+    value_balance
+        .iter()
+        .find(|(a, _)| a == asset)
+        .map(|(_, v)| *v)
+        .unwrap_or(0)
+}
+
+/// Verify one spend: the spent note is included under its anchor, its nullifier is
+/// correctly derived and still fresh, and its value commitment is well-formed. On
+/// success the value commitment is folded into `ctx`.
+fn check_spend(
+    spend: &SpendDescription,
+    commitment_root: &[u8; 32],
+    nullifier_root: &[u8; 32],
+    ctx: &mut BalanceContext,
+) -> bool {
+    // The anchor is a witness field, so it must match a known commitment root;
+    // otherwise a spender could fabricate a tree around a never-deposited note.
+    let anchor_known = spend.anchor == *commitment_root;
+    let included = anchor_known
+        && verify_merkle_path(&spend.commitment, &spend.commitment_proof, &spend.anchor);
+    let nullifier = derive_nullifier(&spend.identity_secret, &spend.external_nullifier);
+    let nullifier_bound = nullifier == spend.nullifier;
+    let fresh = verify_nullifier_absence(&nullifier, &spend.nullifier_proof, nullifier_root);
+    // Bind the spent leaf to the note: the commitment being proven for inclusion
+    // must be the commitment of *this* note's (value, asset, blind).
+    let note_bound = note_commitment(&spend.note) == spend.commitment;
+    let cv = commit_value(spend.note.value, &spend.note.blind, &spend.note.asset_id);
+    let cv_valid = cv.to_bytes() == spend.value_commitment;
+    if included && nullifier_bound && fresh && note_bound && cv_valid {
+        ctx.add_spend(&cv, &spend.note);
+        true
+    } else {
+        false
+    }
+}
+
+/// Verify one output: the new note commitment and the value commitment are both
+/// derived from the *same* note fields (value, asset, blind), so the value the
+/// commitment encodes cannot diverge from the value used in conservation. On
+/// success the value commitment is folded into `ctx`.
+fn check_output(output: &OutputDescription, ctx: &mut BalanceContext) -> bool {
+    let commitment_valid = note_commitment(&output.note) == output.note_commitment;
+    let cv = commit_value(output.note.value, &output.note.blind, &output.note.asset_id);
+    let cv_valid = cv.to_bytes() == output.value_commitment;
+    if commitment_valid && cv_valid {
+        ctx.add_output(&cv, &output.note);
+        true
+    } else {
+        false
+    }
+}
+
+/// Commitment to a shielded note, binding its value, asset, and blinding factor.
+fn note_commitment(note: &ValueNote) -> [u8; 32] {
+    hash_poseidon(&[
+        pack_u64(note.value),
+        Fr::from_bytes(&note.asset_id),
+        Fr::from_bytes(&note.blind),
+    ])
+    .to_bytes()
+}
+
+/// Everything needed to validate one shielded order outside the single-proof
+/// entry point, so a sequencer can feed many of them to a [`batch::BatchValidator`].
+#[derive(Debug, Clone)]
+pub struct OrderWitness {
+    pub spends: Vec<SpendDescription>,
+    pub outputs: Vec<OutputDescription>,
+    pub value_balance: Vec<([u8; 32], i64)>,
+    pub commitment_root: [u8; 32],
+    pub nullifier_root: [u8; 32],
+    /// Aggregate blinding factor of this order's value commitments; `residual`
+    /// equals `binding_blind * H` for a well-formed order.
+    pub binding_blind: [u8; 32],
+}
+
+/// Outcome of validating one order in isolation: its structural validity, the
+/// nullifiers it spends (for dedup across the items of a batch), and the value-commitment
+/// `residual = net - value_balance*G` that the batch binds in aggregate.
+pub struct OrderOutcome {
+    pub structural_valid: bool,
+    pub residual: Point,
+    pub binding_blind: [u8; 32],
+    pub nullifiers: Vec<[u8; 32]>,
+}
+
+/// Validate one order's spends and outputs, returning its [`OrderOutcome`]. This
+/// performs the per-description Merkle/commitment/freshness checks but defers the
+/// value-commitment curve relation to the caller, which batches it.
+pub fn validate_order(witness: &OrderWitness) -> OrderOutcome {
+    let mut ctx = BalanceContext::new();
+    let mut structural_valid = true;
+    let mut nullifiers = Vec::new();
+    for spend in &witness.spends {
+        structural_valid &=
+            check_spend(spend, &witness.commitment_root, &witness.nullifier_root, &mut ctx);
+        nullifiers.push(spend.nullifier);
+    }
+    for output in &witness.outputs {
+        structural_valid &= check_output(output, &mut ctx);
+    }
+    let (net, value_balanced) = ctx.final_check(&witness.value_balance);
+    structural_valid &= value_balanced;
+    // residual = net - sum_asset(value_balance_asset * G_asset); should equal
+    // binding_blind*H when every asset conserves value.
+    let mut expected = Point::IDENTITY;
+    for (asset, declared) in &witness.value_balance {
+        expected = expected.add(&value_base(asset).mul_i64(*declared));
+    }
+    let residual = net.add(&expected.negate());
+    OrderOutcome {
+        structural_valid,
+        residual,
+        binding_blind: witness.binding_blind,
+        nullifiers,
+    }
+}
+
+/// Recompute the Merkle root from `leaf` and its authentication path, returning
+/// whether it matches `root`. At each depth the sibling is hashed on the left or
+/// right according to the corresponding bit of `proof.leaf_index`. Cost is
+/// logarithmic in the accumulator size instead of linear in the spent set.
+fn verify_merkle_path(leaf: &[u8; 32], proof: &MerkleProof, root: &[u8; 32]) -> bool {
+    let mut node = *leaf;
+    for (depth, sibling) in proof.siblings.iter().enumerate() {
+        node = if (proof.leaf_index >> depth) & 1 == 0 {
+            hash_pair(&node, sibling)
+        } else {
+            hash_pair(sibling, &node)
+        };
+    }
+    node == *root
+}
+
+/// Prove a nullifier is absent from the sparse tree: the empty default leaf must
+/// recompute `root` *at the slot keyed by the nullifier*. Binding `leaf_index` to
+/// the nullifier stops a prover presenting some other empty slot's path while the
+/// nullifier's real slot is occupied (which would let a spent nullifier pass as
+/// fresh and enable a double-spend).
+fn verify_nullifier_absence(nullifier: &[u8; 32], proof: &MerkleProof, root: &[u8; 32]) -> bool {
+    proof.leaf_index == nullifier_slot(nullifier, proof.siblings.len() as u32)
+        && verify_merkle_path(&EMPTY_LEAF, proof, root)
+}
+
+/// Slot a nullifier maps to in a sparse tree of the given depth: the low `depth`
+/// bits of its little-endian leading bytes.
+fn nullifier_slot(nullifier: &[u8; 32], depth: u32) -> u64 {
+    let mut index = 0u64;
+    for (i, &byte) in nullifier.iter().enumerate().take(8) {
+        index |= (byte as u64) << (8 * i);
+    }
+    if depth >= 64 {
+        index
+    } else {
+        index & ((1u64 << depth) - 1)
+    }
+}
+
+/// Hash an ordered pair of nodes into their parent.
+#[cfg(not(feature = "sha256-commitments"))]
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hash_poseidon(&[Fr::from_bytes(left), Fr::from_bytes(right)]).to_bytes()
+}
+
+#[cfg(feature = "sha256-commitments")]
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Derive a Semaphore-style external-nullifier-scoped nullifier.
+///
+/// `nullifier = Poseidon(identity_secret, Poseidon(external_nullifier))`. Hashing
+/// the external nullifier first lets it be an arbitrary-length (market id ‖ epoch)
+/// tag while the final hash takes two field elements.
+fn derive_nullifier(identity_secret: &[u8; 32], external_nullifier: &[u8]) -> [u8; 32] {
+    let external_hash = hash_poseidon(&pack_bytes(external_nullifier));
+    hash_poseidon(&[Fr::from_bytes(identity_secret), external_hash]).to_bytes()
+}
+
 /// Validate that order data matches the commitment
+#[cfg(feature = "sp1")]
 fn validate_commitment(order_data: &[u8], commitment: &[u8; 32]) -> bool {
     // Hash the order data
     let computed_hash = hash_data(order_data);
@@ -73,17 +450,137 @@ fn validate_commitment(order_data: &[u8], commitment: &[u8; 32]) -> bool {
     computed_hash == *commitment
 }
 
-/// Hash order data to create commitment using SHA256
+/// Hash order data to create commitment.
+///
+/// Defaults to Poseidon over the scalar field (cheap in the zkVM); the
+/// `sha256-commitments` feature swaps in SHA256 so commitments created by older
+/// hosts still verify.
+#[cfg(all(feature = "sp1", not(feature = "sha256-commitments")))]
+fn hash_data(data: &[u8]) -> [u8; 32] {
+    hash_poseidon(&pack_bytes(data)).to_bytes()
+}
+
+#[cfg(all(feature = "sp1", feature = "sha256-commitments"))]
 fn hash_data(data: &[u8]) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(data);
     hasher.finalize().into()
 }
 
-/// Hash balance for proof (without revealing actual balance) using SHA256
+/// Hash balance for proof (without revealing actual balance).
+#[cfg(all(feature = "sp1", not(feature = "sha256-commitments")))]
+fn hash_balance(balance: u64) -> [u8; 32] {
+    hash_poseidon(&[pack_u64(balance)]).to_bytes()
+}
+
+#[cfg(all(feature = "sp1", feature = "sha256-commitments"))]
 fn hash_balance(balance: u64) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(balance.to_le_bytes());
     hasher.finalize().into()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Build an absence proof for `nullifier` over `siblings`, returning the proof
+    /// and the root it recomputes.
+    fn absence_proof(nullifier: &[u8; 32], siblings: Vec<[u8; 32]>) -> (MerkleProof, [u8; 32]) {
+        let leaf_index = nullifier_slot(nullifier, siblings.len() as u32);
+        let mut node = EMPTY_LEAF;
+        for (depth, sibling) in siblings.iter().enumerate() {
+            node = if (leaf_index >> depth) & 1 == 0 {
+                hash_pair(&node, sibling)
+            } else {
+                hash_pair(sibling, &node)
+            };
+        }
+        (MerkleProof { leaf_index, siblings }, node)
+    }
+
+    #[test]
+    fn merkle_path_accepts_and_rejects() {
+        let leaf = [1u8; 32];
+        let sibling = [2u8; 32];
+        let root = hash_pair(&leaf, &sibling);
+        let proof = MerkleProof {
+            leaf_index: 0,
+            siblings: vec![sibling],
+        };
+        assert!(verify_merkle_path(&leaf, &proof, &root));
+        // A tampered root must not verify.
+        assert!(!verify_merkle_path(&leaf, &proof, &[9u8; 32]));
+    }
+
+    /// A balanced order: spend one note and output an equal note of the same asset.
+    fn balanced_witness() -> OrderWitness {
+        let asset = [3u8; 32];
+        let spend_note = ValueNote {
+            value: 100,
+            asset_id: asset,
+            blind: [4u8; 32],
+        };
+        let spend_commitment = note_commitment(&spend_note);
+        let sibling = [5u8; 32];
+        let commitment_root = hash_pair(&spend_commitment, &sibling);
+
+        let identity_secret = [6u8; 32];
+        let external_nullifier = vec![7u8, 8, 9];
+        let nullifier = derive_nullifier(&identity_secret, &external_nullifier);
+        let (nullifier_proof, nullifier_root) =
+            absence_proof(&nullifier, vec![[10u8; 32], [11u8; 32]]);
+
+        let spend = SpendDescription {
+            nullifier,
+            anchor: commitment_root,
+            value_commitment: commit_value(100, &spend_note.blind, &asset).to_bytes(),
+            note: spend_note,
+            commitment: spend_commitment,
+            commitment_proof: MerkleProof {
+                leaf_index: 0,
+                siblings: vec![sibling],
+            },
+            nullifier_proof,
+            identity_secret,
+            external_nullifier,
+        };
+
+        let out_note = ValueNote {
+            value: 100,
+            asset_id: asset,
+            blind: [12u8; 32],
+        };
+        let output = OutputDescription {
+            note_commitment: note_commitment(&out_note),
+            value_commitment: commit_value(100, &out_note.blind, &asset).to_bytes(),
+            note: out_note,
+        };
+
+        OrderWitness {
+            spends: vec![spend],
+            outputs: vec![output],
+            value_balance: vec![(asset, 0)],
+            commitment_root,
+            nullifier_root,
+            binding_blind: [0u8; 32],
+        }
+    }
+
+    #[test]
+    fn validate_order_accepts_balanced_order() {
+        let outcome = validate_order(&balanced_witness());
+        assert!(outcome.structural_valid);
+    }
+
+    #[test]
+    fn validate_order_rejects_unbalanced_declaration() {
+        let mut witness = balanced_witness();
+        // Spend and output net to zero, but claim a non-zero balance.
+        witness.value_balance = vec![([3u8; 32], 50)];
+        let outcome = validate_order(&witness);
+        assert!(!outcome.structural_valid);
+    }
+}
+